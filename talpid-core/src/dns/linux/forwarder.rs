@@ -0,0 +1,243 @@
+use super::systemd_resolved::RoutingDomain;
+use arc_swap::ArcSwap;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::oneshot,
+};
+
+/// The address the forwarder binds its UDP/TCP sockets to. Only ever reachable from this host.
+/// Also the address `/etc/resolv.conf` is pointed at while the forwarder is in use, see
+/// `super::resolv_conf`.
+pub(crate) const LOOPBACK_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const LOOPBACK_PORT: u16 = 53;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to bind loopback UDP socket")]
+    BindUdp(#[error(source)] std::io::Error),
+
+    #[error(display = "Failed to bind loopback TCP listener")]
+    BindTcp(#[error(source)] std::io::Error),
+}
+
+/// The set of upstream resolvers and split-DNS routing rules the forwarder currently applies.
+/// Swapped in atomically whenever `SystemdResolved::set_dns` is called with a new configuration.
+#[derive(Debug, Clone)]
+pub struct ForwarderConfig {
+    pub upstream_resolvers: Vec<IpAddr>,
+    pub routing_domains: Vec<RoutingDomain>,
+}
+
+impl ForwarderConfig {
+    /// Returns whether `domain` should be forwarded upstream rather than refused, based on the
+    /// configured routing domains. An empty domain list means "forward everything", mirroring
+    /// the systemd-resolved backend's catch-all behavior. `route_only` only controls whether a
+    /// domain is also used as a search-completion suffix (systemd-resolved's `SetLinkDomains`
+    /// semantics, see [`RoutingDomain`]) - it doesn't narrow which domains get routed here, so
+    /// both route-only and search domains match.
+    fn resolves(&self, domain: &str) -> bool {
+        if self.routing_domains.is_empty() {
+            return true;
+        }
+        self.routing_domains
+            .iter()
+            .any(|routing_domain| domain_matches(domain, &routing_domain.domain))
+    }
+}
+
+/// `routing_domain` is the bare domain (no leading `~`) - [`RoutingDomain::domain`] never carries
+/// one, matching the tuples the systemd-resolved backend passes to `SetLinkDomains`.
+fn domain_matches(query_domain: &str, routing_domain: &str) -> bool {
+    if routing_domain == "." {
+        return true;
+    }
+    query_domain == routing_domain || query_domain.ends_with(&format!(".{routing_domain}"))
+}
+
+/// A minimal loopback caching-free DNS forwarder used when systemd-resolved's D-Bus interface
+/// isn't available. Structured like aardvark-dns's serve loop: a UDP/TCP listener pair on
+/// loopback that forwards matching queries to the configured upstream resolvers, and
+/// answers NXDOMAIN for everything else so behavior matches the systemd-resolved split-DNS path.
+pub struct LoopbackDnsForwarder {
+    config: Arc<ArcSwap<ForwarderConfig>>,
+    udp_task: tokio::task::JoinHandle<()>,
+    tcp_task: tokio::task::JoinHandle<()>,
+    udp_shutdown_tx: Option<oneshot::Sender<()>>,
+    tcp_shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl LoopbackDnsForwarder {
+    pub async fn start(initial_config: ForwarderConfig) -> Result<Self> {
+        let bind_addr = SocketAddr::new(LOOPBACK_ADDR, LOOPBACK_PORT);
+        let udp_socket = UdpSocket::bind(bind_addr).await.map_err(Error::BindUdp)?;
+        let tcp_listener = TcpListener::bind(bind_addr).await.map_err(Error::BindTcp)?;
+
+        let config = Arc::new(ArcSwap::from_pointee(initial_config));
+        let (shutdown_tx, mut udp_shutdown_rx) = oneshot::channel();
+        let (tcp_shutdown_tx, mut tcp_shutdown_rx) = oneshot::channel();
+
+        let udp_config = config.clone();
+        let udp_task = tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                tokio::select! {
+                    _ = &mut udp_shutdown_rx => break,
+                    received = udp_socket.recv_from(&mut buf) => {
+                        let (len, from) = match received {
+                            Ok(result) => result,
+                            Err(error) => {
+                                log::error!("Failed to receive DNS query: {}", error);
+                                continue;
+                            }
+                        };
+                        let config = udp_config.load();
+                        if let Some(reply) = handle_query(&config, &buf[..len]).await {
+                            if let Err(error) = udp_socket.send_to(&reply, from).await {
+                                log::error!("Failed to send DNS reply: {}", error);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        let tcp_config = config.clone();
+        let tcp_task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut tcp_shutdown_rx => break,
+                    accepted = tcp_listener.accept() => {
+                        let (stream, _) = match accepted {
+                            Ok(result) => result,
+                            Err(error) => {
+                                log::error!("Failed to accept DNS connection: {}", error);
+                                continue;
+                            }
+                        };
+                        let config = tcp_config.load_full();
+                        tokio::spawn(serve_tcp_connection(stream, config));
+                    }
+                }
+            }
+        });
+
+        Ok(LoopbackDnsForwarder {
+            config,
+            udp_task,
+            tcp_task,
+            udp_shutdown_tx: Some(shutdown_tx),
+            tcp_shutdown_tx: Some(tcp_shutdown_tx),
+        })
+    }
+
+    /// Atomically replaces the forwarder's upstream resolvers and routing domains, e.g. when
+    /// `SystemdResolved::set_dns` is called again with a different tunnel configuration.
+    pub fn set_config(&self, new_config: ForwarderConfig) {
+        self.config.store(Arc::new(new_config));
+    }
+
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown_tx) = self.udp_shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        if let Some(shutdown_tx) = self.tcp_shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        let _ = self.udp_task.await;
+        let _ = self.tcp_task.await;
+    }
+}
+
+async fn serve_tcp_connection(
+    mut stream: tokio::net::TcpStream,
+    config: Arc<ForwarderConfig>,
+) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut len_buf = [0u8; 2];
+    if stream.read_exact(&mut len_buf).await.is_err() {
+        return;
+    }
+    let query_len = u16::from_be_bytes(len_buf) as usize;
+    let mut query = vec![0u8; query_len];
+    if stream.read_exact(&mut query).await.is_err() {
+        return;
+    }
+
+    if let Some(reply) = handle_query(&config, &query).await {
+        let reply_len = (reply.len() as u16).to_be_bytes();
+        let _ = stream.write_all(&reply_len).await;
+        let _ = stream.write_all(&reply).await;
+    }
+}
+
+/// Forwards `query` upstream if its question name matches a routing domain, otherwise answers
+/// with NXDOMAIN locally. Returns `None` if the query couldn't even be parsed.
+async fn handle_query(config: &ForwarderConfig, query: &[u8]) -> Option<Vec<u8>> {
+    let domain = parse_question_name(query)?;
+
+    if !config.resolves(&domain) {
+        return Some(nxdomain_reply(query));
+    }
+
+    for resolver in &config.upstream_resolvers {
+        if let Some(reply) = forward_to_upstream(*resolver, query).await {
+            return Some(reply);
+        }
+    }
+
+    Some(nxdomain_reply(query))
+}
+
+async fn forward_to_upstream(resolver: IpAddr, query: &[u8]) -> Option<Vec<u8>> {
+    let bind_addr = if resolver.is_ipv6() {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind((bind_addr, 0)).await.ok()?;
+    socket.connect((resolver, 53)).await.ok()?;
+    socket.send(query).await.ok()?;
+
+    let mut buf = [0u8; 512];
+    let len = tokio::time::timeout(UPSTREAM_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .ok()?
+        .ok()?;
+    Some(buf[..len].to_vec())
+}
+
+/// Extracts the dotted question name from a DNS query's first question section.
+fn parse_question_name(query: &[u8]) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut offset = 12; // after the fixed-size header
+    loop {
+        let length = *query.get(offset)? as usize;
+        if length == 0 {
+            break;
+        }
+        offset += 1;
+        let label = query.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += length;
+    }
+    Some(labels.join("."))
+}
+
+/// Builds a minimal NXDOMAIN reply that echoes the query's ID and question section.
+fn nxdomain_reply(query: &[u8]) -> Vec<u8> {
+    let mut reply = query.to_vec();
+    if reply.len() >= 4 {
+        reply[2] = 0x81; // response, recursion desired
+        reply[3] = 0x83; // recursion available, RCODE = NXDOMAIN (3)
+    }
+    reply
+}
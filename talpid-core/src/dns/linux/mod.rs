@@ -0,0 +1,10 @@
+mod forwarder;
+mod resolv_conf;
+mod routing;
+mod systemd_resolved;
+
+pub use forwarder::{Error as ForwarderError, ForwarderConfig, LoopbackDnsForwarder};
+pub use systemd_resolved::{
+    DnsLeakEvent, DnsOverTlsMode, DnssecMode, Error, Result, RoutingDomain, SystemdResolved,
+    SystemdDbusError,
+};
@@ -0,0 +1,54 @@
+use std::{fs, io, net::IpAddr};
+
+/// Where the loopback forwarder fallback points the system while it's in use. systemd-resolved
+/// itself owns this path the rest of the time (usually as a symlink into `/run/systemd/resolve/`),
+/// so this is only ever touched from `SystemdResolved::set_dns_via_forwarder`/`reset` when the
+/// D-Bus interface isn't available.
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to read {}", _0)]
+    Read(String, #[error(source)] io::Error),
+
+    #[error(display = "Failed to write {}", _0)]
+    Write(String, #[error(source)] io::Error),
+}
+
+/// Backs up and overwrites `/etc/resolv.conf` for as long as the loopback forwarder fallback is
+/// managing DNS, then restores whatever was there before once dropped via [`Self::restore`].
+pub struct ManagedResolvConf {
+    backup: Option<Vec<u8>>,
+}
+
+impl ManagedResolvConf {
+    /// Backs up the current `/etc/resolv.conf`, if any, and points it at `nameserver`.
+    pub fn set(nameserver: IpAddr) -> Result<Self> {
+        let backup = match fs::read(RESOLV_CONF_PATH) {
+            Ok(contents) => Some(contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(Error::Read(RESOLV_CONF_PATH.to_owned(), error)),
+        };
+
+        fs::write(RESOLV_CONF_PATH, format!("nameserver {}\n", nameserver))
+            .map_err(|error| Error::Write(RESOLV_CONF_PATH.to_owned(), error))?;
+
+        Ok(ManagedResolvConf { backup })
+    }
+
+    /// Restores whatever `/etc/resolv.conf` contained before [`Self::set`] was called, or removes
+    /// it entirely if there was nothing there to begin with.
+    pub fn restore(self) -> Result<()> {
+        match self.backup {
+            Some(contents) => fs::write(RESOLV_CONF_PATH, contents)
+                .map_err(|error| Error::Write(RESOLV_CONF_PATH.to_owned(), error)),
+            None => match fs::remove_file(RESOLV_CONF_PATH) {
+                Ok(()) => Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(error) => Err(Error::Write(RESOLV_CONF_PATH.to_owned(), error)),
+            },
+        }
+    }
+}
@@ -1,16 +1,21 @@
 use crate::linux::{iface_index, IfaceIndexLookupError};
 use futures::{channel::mpsc, StreamExt};
 use std::{
+    collections::{HashMap, HashSet},
     net::IpAddr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
     thread,
+    time::Duration,
 };
 use talpid_dbus::systemd_resolved::{AsyncHandle, DnsState, SystemdResolved as DbusInterface};
 use talpid_types::ErrorExt;
 
+use super::forwarder::{self, ForwarderConfig, LoopbackDnsForwarder, LOOPBACK_ADDR};
+use super::resolv_conf::{self, ManagedResolvConf};
+
 pub(crate) use talpid_dbus::systemd_resolved::Error as SystemdDbusError;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -25,14 +30,382 @@ pub enum Error {
 
     #[error(display = "Failed to spawn DNS interface monitor")]
     SpawnInterfaceMonitor(#[error(source)] super::routing::Error),
+
+    #[error(display = "Failed to start the loopback DNS forwarder fallback")]
+    ForwarderError(#[error(source)] forwarder::Error),
+
+    #[error(display = "Failed to point /etc/resolv.conf at the loopback DNS forwarder fallback")]
+    ResolvConfError(#[error(source)] resolv_conf::Error),
 }
 
 use super::routing::{DnsConfig, DnsRouteMonitor};
 
+/// Computes the `(domain, route_only)` pairs to apply to the tunnel interface: the configured
+/// split-DNS routing domains if any are set, otherwise the previous all-or-nothing behavior of
+/// routing everything through the tunnel when it's the only managed interface.
+fn compute_tunnel_domains(
+    routing_domains: &[RoutingDomain],
+    config: &[DnsConfig],
+    tunnel_index: u32,
+) -> Vec<(String, bool)> {
+    if !routing_domains.is_empty() {
+        return routing_domains
+            .iter()
+            .map(|domain| (domain.domain.clone(), domain.route_only))
+            .collect();
+    }
+
+    if config.len() == 1 && config[0].interface == tunnel_index {
+        vec![(".".to_owned(), true)]
+    } else {
+        vec![]
+    }
+}
+
+/// Per-link DNS-over-TLS enforcement level, mirrored from the public DNS settings and applied
+/// via `SetLinkDNSOverTLS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsOverTlsMode {
+    Yes,
+    Opportunistic,
+    No,
+}
+
+impl Default for DnsOverTlsMode {
+    fn default() -> Self {
+        DnsOverTlsMode::Opportunistic
+    }
+}
+
+impl DnsOverTlsMode {
+    fn as_dbus_str(self) -> &'static str {
+        match self {
+            DnsOverTlsMode::Yes => "yes",
+            DnsOverTlsMode::Opportunistic => "opportunistic",
+            DnsOverTlsMode::No => "no",
+        }
+    }
+}
+
+/// Per-link DNSSEC validation level, mirrored from the public DNS settings and applied via
+/// `SetLinkDNSSEC`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnssecMode {
+    Yes,
+    AllowDowngrade,
+    No,
+}
+
+impl Default for DnssecMode {
+    fn default() -> Self {
+        DnssecMode::AllowDowngrade
+    }
+}
+
+impl DnssecMode {
+    fn as_dbus_str(self) -> &'static str {
+        match self {
+            DnssecMode::Yes => "yes",
+            DnssecMode::AllowDowngrade => "allow-downgrade",
+            DnssecMode::No => "no",
+        }
+    }
+}
+
+/// A split-DNS routing domain, mirrored from the public DNS settings. `route_only` matches the
+/// `~example.com`-style routing domains accepted by systemd-resolved's `SetLinkDomains`: when
+/// true, only queries matching `domain` are sent to the tunnel's resolvers; when false, `domain`
+/// is a regular search domain.
+#[derive(Debug, Clone)]
+pub struct RoutingDomain {
+    pub domain: String,
+    pub route_only: bool,
+}
+
+/// How often the resolver pool probes each configured resolver and re-sorts by health.
+const RESOLVER_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the failure counters are decayed. Deliberately much longer than
+/// `RESOLVER_PROBE_INTERVAL`: decaying on every probe round would reset a dead resolver's
+/// counter before it could ever reach `RESOLVER_FAILURE_THRESHOLD`, defeating failover.
+const RESOLVER_DECAY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Consecutive probe failures after which a resolver is pushed to the end of the list.
+const RESOLVER_FAILURE_THRESHOLD: u32 = 3;
+/// The SRTT assumed for a resolver that hasn't been probed yet.
+const DEFAULT_RESOLVER_SRTT: Duration = Duration::from_millis(50);
+/// Weight the previous SRTT keeps when a new RTT sample arrives (the sample itself gets
+/// `1.0 - SRTT_SMOOTHING_FACTOR`), as in trust-dns's name server pool.
+const SRTT_SMOOTHING_FACTOR: f64 = 0.9;
+/// Factor the failure counter is decayed by every [`RESOLVER_DECAY_INTERVAL`], so a penalized
+/// resolver can recover once it starts responding again.
+const FAILURE_DECAY_FACTOR: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+struct ResolverState {
+    srtt: Duration,
+    consecutive_failures: u32,
+}
+
+impl Default for ResolverState {
+    fn default() -> Self {
+        ResolverState {
+            srtt: DEFAULT_RESOLVER_SRTT,
+            consecutive_failures: 0,
+        }
+    }
+}
+
+/// Tracks the health of each configured resolver so the applied list can be re-ordered towards
+/// the fastest, most reliable servers instead of a fixed order, modeled on trust-dns's
+/// `name_server_pool` scoring.
+#[derive(Debug, Default)]
+struct ResolverPool {
+    states: HashMap<IpAddr, ResolverState>,
+}
+
+impl ResolverPool {
+    fn record_probe_result(&mut self, resolver: IpAddr, rtt: Option<Duration>) {
+        let state = self.states.entry(resolver).or_default();
+        match rtt {
+            Some(sample) => {
+                let srtt_secs = SRTT_SMOOTHING_FACTOR * state.srtt.as_secs_f64()
+                    + (1.0 - SRTT_SMOOTHING_FACTOR) * sample.as_secs_f64();
+                state.srtt = Duration::from_secs_f64(srtt_secs);
+                state.consecutive_failures = 0;
+            }
+            None => {
+                state.consecutive_failures += 1;
+            }
+        }
+    }
+
+    /// Exponentially decays every resolver's failure counter, so a server that was briefly
+    /// unreachable can climb back up the list once it stabilizes.
+    fn decay(&mut self) {
+        for state in self.states.values_mut() {
+            state.consecutive_failures =
+                (state.consecutive_failures as f64 * FAILURE_DECAY_FACTOR) as u32;
+        }
+    }
+
+    /// Returns `resolvers` re-ordered ascending by SRTT, with any resolver that has failed at
+    /// least [`RESOLVER_FAILURE_THRESHOLD`] probes in a row pushed to the end.
+    fn sorted(&self, resolvers: &[IpAddr]) -> Vec<IpAddr> {
+        let mut sorted = resolvers.to_vec();
+        sorted.sort_by_key(|resolver| {
+            let state = self.states.get(resolver).copied().unwrap_or_default();
+            let is_failing = state.consecutive_failures >= RESOLVER_FAILURE_THRESHOLD;
+            (is_failing, state.srtt)
+        });
+        sorted
+    }
+}
+
+/// Sends a lightweight probe query to `resolver` and returns the round-trip time on success.
+async fn probe_resolver(resolver: IpAddr, dns_over_tls_mode: DnsOverTlsMode) -> Option<Duration> {
+    // When DoT is enforced on the tunnel interface, a resolver that only answers over TLS/853
+    // would time out on a plaintext UDP/53 probe despite being perfectly healthy, and get
+    // wrongly pushed to the end of `sorted()`'s ordering.
+    if dns_over_tls_mode == DnsOverTlsMode::Yes {
+        return probe_resolver_dot(resolver).await;
+    }
+
+    use tokio::{net::UdpSocket, time::Instant};
+
+    // A minimal DNS query for the root NS records, just to measure whether the resolver
+    // responds and how fast - the contents of the reply don't matter.
+    const QUERY: [u8; 17] = [
+        0x00, 0x00, // transaction ID
+        0x01, 0x00, // standard query, recursion desired
+        0x00, 0x01, // one question
+        0x00, 0x00, // zero answers
+        0x00, 0x00, // zero authority records
+        0x00, 0x00, // zero additional records
+        0x00, // root domain name
+        0x00, 0x02, // QTYPE NS
+        0x00, 0x01, // QCLASS IN
+    ];
+
+    let bind_addr: IpAddr = if resolver.is_ipv6() {
+        "::".parse().unwrap()
+    } else {
+        "0.0.0.0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind((bind_addr, 0)).await.ok()?;
+    socket.connect((resolver, 53)).await.ok()?;
+
+    let start = Instant::now();
+    socket.send(&QUERY).await.ok()?;
+
+    let mut buf = [0u8; 512];
+    tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+        .await
+        .ok()?
+        .ok()?;
+
+    Some(start.elapsed())
+}
+
+/// Probes a resolver that's only expected to answer on the DNS-over-TLS port (853). A full TLS
+/// handshake isn't needed just to score reachability/RTT, so this measures how long a bare TCP
+/// handshake to that port takes instead.
+async fn probe_resolver_dot(resolver: IpAddr) -> Option<Duration> {
+    use tokio::{net::TcpStream, time::Instant};
+
+    let start = Instant::now();
+    tokio::time::timeout(Duration::from_secs(2), TcpStream::connect((resolver, 853)))
+        .await
+        .ok()?
+        .ok()?;
+
+    Some(start.elapsed())
+}
+
+/// How often the leak-detection subsystem re-reads non-tunnel interfaces' resolvers from
+/// systemd-resolved to confirm they still match what we applied.
+const LEAK_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Emitted by the leak-detection subsystem when a non-tunnel interface's currently-configured
+/// resolvers no longer match what we applied to it - either a foreign process reverted our
+/// config, or the physical link regained its own resolvers (e.g. a DHCP re-lease). Either way,
+/// DNS traffic on that interface could be leaking outside the tunnel.
+#[derive(Debug, Clone)]
+pub enum DnsLeakEvent {
+    DnsLeakDetected {
+        interface_index: u32,
+        observed_servers: Vec<IpAddr>,
+    },
+}
+
+/// For each non-tunnel interface we're managing, re-reads its currently-configured resolvers
+/// from systemd-resolved and compares them against what we applied. On a mismatch, re-applies
+/// our config and reports a [`DnsLeakEvent`] so the caller can log it or tear down the tunnel.
+///
+/// `outstanding_leaks` tracks which interfaces currently have an unacknowledged leak event in
+/// flight, so a leak that persists across multiple [`LEAK_CHECK_INTERVAL`] ticks (e.g. because
+/// re-applying our config keeps failing) is only reported once instead of flooding `leak_tx` -
+/// the channel is unbounded and the consumer may lag or never catch up. The event fires again
+/// only after the interface is observed to match our config, i.e. the leak actually clears.
+async fn check_for_dns_leaks(
+    dbus_interface: &AsyncHandle,
+    current_config: &Arc<Mutex<Vec<DnsConfig>>>,
+    tunnel_index: u32,
+    leak_tx: &mpsc::UnboundedSender<DnsLeakEvent>,
+    outstanding_leaks: &mut HashSet<u32>,
+) {
+    let configs = { current_config.lock().unwrap().clone() };
+    for config in &configs {
+        if config.interface == tunnel_index {
+            continue;
+        }
+
+        let observed_state = match dbus_interface.get_dns(config.interface).await {
+            Ok(state) => state,
+            Err(error) => {
+                log::error!(
+                    "Failed to read current DNS config for leak check on interface {}: {}",
+                    config.interface,
+                    error
+                );
+                continue;
+            }
+        };
+
+        let mut observed_servers = observed_state.servers.clone();
+        let mut expected_servers = config.resolvers.clone();
+        observed_servers.sort();
+        expected_servers.sort();
+
+        if observed_servers != expected_servers {
+            log::warn!(
+                "Possible DNS leak on interface {}: expected {:?}, found {:?}",
+                config.interface,
+                expected_servers,
+                observed_servers
+            );
+
+            if let Err(error) = dbus_interface
+                .set_dns(config.interface, config.resolvers.clone())
+                .await
+            {
+                log::error!(
+                    "Failed to re-apply DNS config after leak detection: {}",
+                    error
+                );
+            }
+
+            if outstanding_leaks.insert(config.interface) {
+                let _ = leak_tx.unbounded_send(DnsLeakEvent::DnsLeakDetected {
+                    interface_index: config.interface,
+                    observed_servers,
+                });
+            }
+        } else {
+            outstanding_leaks.remove(&config.interface);
+        }
+    }
+}
+
+/// Probes every resolver in `current_config`, updates `resolver_pool` with the results, and
+/// returns a re-sorted config if any interface's resolver order actually changed. Only the
+/// tunnel interface's resolvers are probed over DoT when `dns_over_tls_mode` enforces it - other
+/// managed interfaces keep their own plaintext resolution policy (see the tunnel-only DoT/DNSSEC
+/// gating in `set_dns`).
+async fn reorder_resolvers_by_health(
+    resolver_pool: &Arc<Mutex<ResolverPool>>,
+    current_config: &Arc<Mutex<Vec<DnsConfig>>>,
+    tunnel_index: u32,
+    dns_over_tls_mode: DnsOverTlsMode,
+) -> Option<Vec<DnsConfig>> {
+    let configs = { current_config.lock().unwrap().clone() };
+    for config in &configs {
+        let dns_over_tls_mode = if config.interface == tunnel_index {
+            dns_over_tls_mode
+        } else {
+            DnsOverTlsMode::No
+        };
+        for resolver in &config.resolvers {
+            let rtt = probe_resolver(*resolver, dns_over_tls_mode).await;
+            resolver_pool.lock().unwrap().record_probe_result(*resolver, rtt);
+        }
+    }
+
+    let mut changed = false;
+    let mut reordered = configs;
+    for config in &mut reordered {
+        let sorted = resolver_pool.lock().unwrap().sorted(&config.resolvers);
+        if sorted != config.resolvers {
+            config.resolvers = sorted;
+            changed = true;
+        }
+    }
+
+    if changed {
+        Some(reordered)
+    } else {
+        None
+    }
+}
+
+/// The DNS-over-TLS/DNSSEC modes an interface had before we took it over, so `reset` can put
+/// them back.
+#[derive(Debug, Clone)]
+struct InitialLinkDnsModes {
+    interface_index: u32,
+    dns_over_tls: String,
+    dnssec: String,
+}
+
 pub struct SystemdResolved {
-    pub dbus_interface: AsyncHandle,
+    pub dbus_interface: Option<AsyncHandle>,
+    forwarder: Option<LoopbackDnsForwarder>,
+    resolv_conf: Option<ManagedResolvConf>,
     current_config: Arc<Mutex<Vec<DnsConfig>>>,
     initial_states: Arc<Mutex<Vec<DnsState>>>,
+    initial_link_dns_modes: Arc<Mutex<Vec<InitialLinkDnsModes>>>,
+    dns_over_tls_mode: DnsOverTlsMode,
+    dnssec_mode: DnssecMode,
+    routing_domains: Vec<RoutingDomain>,
+    resolver_pool: Arc<Mutex<ResolverPool>>,
     tunnel_index: u32,
     route_monitor: Option<(DnsRouteMonitor, tokio::task::JoinHandle<()>)>,
     watcher: Option<(thread::JoinHandle<()>, Arc<AtomicBool>)>,
@@ -40,13 +413,33 @@ pub struct SystemdResolved {
 
 
 impl SystemdResolved {
+    /// Connects to systemd-resolved over D-Bus. If the interface isn't available on this
+    /// system, DNS management falls back to the built-in loopback forwarder the first time
+    /// [`Self::set_dns`] is called, instead of giving up entirely.
     pub fn new() -> Result<Self> {
-        let dbus_interface = DbusInterface::new()?.async_handle();
+        let dbus_interface = match DbusInterface::new() {
+            Ok(interface) => Some(interface.async_handle()),
+            Err(error) => {
+                log::warn!(
+                    "systemd-resolved is unavailable, DNS will be managed via the loopback \
+                     forwarder fallback instead: {}",
+                    error
+                );
+                None
+            }
+        };
 
         let systemd_resolved = SystemdResolved {
             dbus_interface,
+            forwarder: None,
+            resolv_conf: None,
             current_config: Arc::new(Mutex::new(vec![])),
             initial_states: Arc::new(Mutex::new(vec![])),
+            initial_link_dns_modes: Arc::new(Mutex::new(vec![])),
+            dns_over_tls_mode: DnsOverTlsMode::default(),
+            dnssec_mode: DnssecMode::default(),
+            routing_domains: vec![],
+            resolver_pool: Arc::new(Mutex::new(ResolverPool::default())),
             tunnel_index: 0,
             route_monitor: None,
             watcher: None,
@@ -55,7 +448,54 @@ impl SystemdResolved {
         Ok(systemd_resolved)
     }
 
-    pub async fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
+    /// Overrides the DNS-over-TLS and DNSSEC enforcement levels applied to the tunnel
+    /// interface. Must be called before [`Self::set_dns`] to take effect.
+    pub fn set_dns_over_tls_and_dnssec_modes(
+        &mut self,
+        dns_over_tls_mode: DnsOverTlsMode,
+        dnssec_mode: DnssecMode,
+    ) {
+        self.dns_over_tls_mode = dns_over_tls_mode;
+        self.dnssec_mode = dnssec_mode;
+    }
+
+    /// Overrides the split-DNS routing domains applied to the tunnel interface. Must be called
+    /// before [`Self::set_dns`] to take effect. An empty list falls back to the previous
+    /// behavior of routing either everything or nothing through the tunnel, depending on whether
+    /// the tunnel interface is the only one being managed.
+    pub fn set_routing_domains(&mut self, routing_domains: Vec<RoutingDomain>) {
+        self.routing_domains = routing_domains;
+    }
+
+    /// Computes the `(domain, route_only)` pairs to apply to the tunnel interface for the given
+    /// set of managed interfaces.
+    fn tunnel_domains(&self, config: &[DnsConfig]) -> Vec<(String, bool)> {
+        compute_tunnel_domains(&self.routing_domains, config, self.tunnel_index)
+    }
+
+    async fn apply_link_dns_modes(&self, dbus_interface: &AsyncHandle, interface_index: u32) -> Result<()> {
+        dbus_interface
+            .set_link_dns_over_tls(interface_index, self.dns_over_tls_mode.as_dbus_str())
+            .await
+            .map_err(Error::SystemdResolvedError)?;
+        dbus_interface
+            .set_link_dnssec(interface_index, self.dnssec_mode.as_dbus_str())
+            .await
+            .map_err(Error::SystemdResolvedError)?;
+        Ok(())
+    }
+
+    pub async fn set_dns(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+    ) -> Result<mpsc::UnboundedReceiver<DnsLeakEvent>> {
+        let dbus_interface = match self.dbus_interface.clone() {
+            Some(dbus_interface) => dbus_interface,
+            None => return self.set_dns_via_forwarder(servers).await,
+        };
+
+        let (leak_tx, leak_rx) = mpsc::unbounded();
         let (update_tx, mut update_rx) = mpsc::unbounded();
         let (monitor, initial_config) = super::routing::spawn_monitor(servers.to_vec(), update_tx)
             .await
@@ -66,38 +506,64 @@ impl SystemdResolved {
         let mut last_result = Ok(());
 
         for iface_config in &initial_config {
-            let initial_state = match self.dbus_interface.get_dns(iface_config.interface).await {
+            let initial_state = match dbus_interface.get_dns(iface_config.interface).await {
                 Ok(state) => state,
                 Err(error) => {
                     last_result = Err(Error::SystemdResolvedError(error));
                     break;
                 }
             };
-            if let Err(error) = self
-                .dbus_interface
+            if let Err(error) = dbus_interface
                 .set_dns(iface_config.interface, iface_config.resolvers.clone())
                 .await
             {
                 last_result = Err(Error::SystemdResolvedError(error));
                 break;
             }
-            self.initial_states.lock().unwrap().push(initial_state);
-        }
 
-        if last_result.is_ok() {
-            if initial_config.len() == 1 && initial_config[0].interface == tunnel_index {
+            // DoT/DNSSEC enforcement only applies to the tunnel interface - physical links keep
+            // whatever resolution policy they already had.
+            if iface_config.interface == tunnel_index {
+                let prior_dns_over_tls = dbus_interface
+                    .get_link_dns_over_tls(iface_config.interface)
+                    .await
+                    .unwrap_or_else(|_| "no".to_owned());
+                let prior_dnssec = dbus_interface
+                    .get_link_dnssec(iface_config.interface)
+                    .await
+                    .unwrap_or_else(|_| "no".to_owned());
+                self.initial_link_dns_modes
+                    .lock()
+                    .unwrap()
+                    .push(InitialLinkDnsModes {
+                        interface_index: iface_config.interface,
+                        dns_over_tls: prior_dns_over_tls,
+                        dnssec: prior_dnssec,
+                    });
                 if let Err(error) = self
-                    .dbus_interface
-                    .set_domains(tunnel_index, &[(".", true)])
+                    .apply_link_dns_modes(&dbus_interface, iface_config.interface)
                     .await
                 {
-                    last_result = Err(Error::SystemdResolvedError(error));
-                }
-            } else {
-                if let Err(error) = self.dbus_interface.set_domains(tunnel_index, &[]).await {
-                    last_result = Err(Error::SystemdResolvedError(error));
+                    last_result = Err(error);
+                    break;
                 }
             }
+
+            self.initial_states.lock().unwrap().push(initial_state);
+        }
+
+        if last_result.is_ok() {
+            let domains = self.tunnel_domains(&initial_config);
+            let domain_refs: Vec<(&str, bool)> = domains
+                .iter()
+                .map(|(domain, route_only)| (domain.as_str(), *route_only))
+                .collect();
+            if let Err(error) = dbus_interface
+                .set_domains(tunnel_index, &domain_refs)
+                .await
+            {
+                last_result = Err(Error::SystemdResolvedError(error));
+            }
         }
 
         if let Err(error) = last_result {
@@ -112,16 +578,71 @@ impl SystemdResolved {
         let ignore_config_changes = Arc::new(AtomicBool::new(false));
 
         self.watcher = Some(self.spawn_watcher_thread(
+            &dbus_interface,
             tunnel_index,
             self.current_config.clone(),
             ignore_config_changes.clone(),
         ));
 
-        let dbus_interface = self.dbus_interface.clone();
         let initial_states = self.initial_states.clone();
         let current_config = self.current_config.clone();
+        let routing_domains = self.routing_domains.clone();
+        let resolver_pool = self.resolver_pool.clone();
+        let dns_over_tls_mode = self.dns_over_tls_mode;
+        let leak_check_dbus_interface = dbus_interface.clone();
+        let leak_check_config = self.current_config.clone();
         let join_handle = tokio::spawn(async move {
-            while let Some(mut new_config) = update_rx.next().await {
+            let mut probe_interval = tokio::time::interval(RESOLVER_PROBE_INTERVAL);
+            let mut decay_interval = tokio::time::interval(RESOLVER_DECAY_INTERVAL);
+            let mut leak_check_interval = tokio::time::interval(LEAK_CHECK_INTERVAL);
+            let mut outstanding_leaks = HashSet::new();
+            loop {
+                let mut new_config = tokio::select! {
+                    new_config = update_rx.next() => match new_config {
+                        Some(new_config) => new_config,
+                        None => break,
+                    },
+                    _ = leak_check_interval.tick() => {
+                        check_for_dns_leaks(
+                            &leak_check_dbus_interface,
+                            &leak_check_config,
+                            tunnel_index,
+                            &leak_tx,
+                            &mut outstanding_leaks,
+                        ).await;
+                        continue;
+                    },
+                    _ = decay_interval.tick() => {
+                        resolver_pool.lock().unwrap().decay();
+                        continue;
+                    },
+                    _ = probe_interval.tick() => {
+                        let reordered = reorder_resolvers_by_health(
+                            &resolver_pool,
+                            &current_config,
+                            tunnel_index,
+                            dns_over_tls_mode,
+                        ).await;
+                        if let Some(reordered_config) = reordered {
+                            ignore_config_changes.store(true, Ordering::Release);
+                            for iface_config in &reordered_config {
+                                if let Err(error) = dbus_interface
+                                    .set_dns(iface_config.interface, iface_config.resolvers.clone())
+                                    .await
+                                {
+                                    log::error!(
+                                        "Failed to re-apply re-ordered resolvers: {}\n{}",
+                                        iface_config,
+                                        error.display_chain()
+                                    );
+                                }
+                            }
+                            *current_config.lock().unwrap() = reordered_config;
+                            ignore_config_changes.store(false, Ordering::Release);
+                        }
+                        continue;
+                    }
+                };
                 let mut new_initial_states = { initial_states.lock().unwrap().clone() };
                 new_initial_states.sort_by(|a, b| a.interface_index.cmp(&b.interface_index));
                 new_config.sort_by(|a, b| a.interface.cmp(&b.interface));
@@ -184,16 +705,18 @@ impl SystemdResolved {
                             error.display_chain()
                         );
                     }
+                    // DoT/DNSSEC enforcement is scoped to the tunnel interface, which this loop
+                    // never reaches (see the `continue` above), so there's nothing to re-assert
+                    // here.
                 }
 
-                let tunnel_domains =
-                    if new_config.len() == 1 && new_config[0].interface == tunnel_index {
-                        &[(".", true)][..]
-                    } else {
-                        &[][..]
-                    };
+                let tunnel_domains = compute_tunnel_domains(&routing_domains, &new_config, tunnel_index);
+                let tunnel_domain_refs: Vec<(&str, bool)> = tunnel_domains
+                    .iter()
+                    .map(|(domain, route_only)| (domain.as_str(), *route_only))
+                    .collect();
                 if let Err(error) = dbus_interface
-                    .set_domains(tunnel_index, tunnel_domains)
+                    .set_domains(tunnel_index, &tunnel_domain_refs)
                     .await
                 {
                     log::error!(
@@ -213,16 +736,20 @@ impl SystemdResolved {
         });
         self.route_monitor = Some((monitor, join_handle));
 
-        Ok(())
+        Ok(leak_rx)
     }
 
     fn spawn_watcher_thread(
         &mut self,
+        dbus_interface: &AsyncHandle,
         tunnel_index: u32,
         current_config: Arc<Mutex<Vec<DnsConfig>>>,
         disable_watcher: Arc<AtomicBool>,
     ) -> (thread::JoinHandle<()>, Arc<AtomicBool>) {
-        let dbus_interface = self.dbus_interface.handle().clone();
+        let dbus_interface = dbus_interface.handle().clone();
+        let dns_over_tls_mode = self.dns_over_tls_mode;
+        let dnssec_mode = self.dnssec_mode;
+        let routing_domains = self.routing_domains.clone();
         let should_shutdown = Arc::new(AtomicBool::new(false));
         let watch_shutdown = should_shutdown.clone();
         let callback_shutdown = should_shutdown.clone();
@@ -243,21 +770,41 @@ impl SystemdResolved {
                             .filter(|server| server.iface_index == config.interface as i32)
                             .map(|server| server.address)
                             .collect();
-                        if current_servers != config.resolvers {
+                        // Compare as sets: the resolver pool re-orders `config.resolvers` on
+                        // its own, and that reordering must not be mistaken for a foreign
+                        // change and fought by this watcher.
+                        let mut observed = current_servers.clone();
+                        let mut expected = config.resolvers.clone();
+                        observed.sort();
+                        expected.sort();
+                        if observed != expected {
                             log::debug!("DNS config for interface {} changed, currently applied servers - {:?}", config.interface, current_servers);
                             if let Err(err) = dbus_interface.set_dns(config.interface, config.resolvers.clone())
                             {
                                 log::error!("Failed to re-apply DNS config - {}", err);
                             }
+                            // DoT/DNSSEC enforcement only applies to the tunnel interface; other
+                            // managed interfaces (e.g. physical links) are left at their own modes.
+                            if config.interface == tunnel_index {
+                                if let Err(err) = dbus_interface.set_link_dns_over_tls(config.interface, dns_over_tls_mode.as_dbus_str())
+                                {
+                                    log::error!("Failed to re-assert DNS-over-TLS mode - {}", err);
+                                }
+                                if let Err(err) = dbus_interface.set_link_dnssec(config.interface, dnssec_mode.as_dbus_str())
+                                {
+                                    log::error!("Failed to re-assert DNSSEC mode - {}", err);
+                                }
+                            }
                             anything_changed = true;
                         }
                     }
                     if anything_changed {
-                        let result = if configs.len() == 1 && configs[0].interface == tunnel_index {
-                            dbus_interface.set_domains(tunnel_index, &[(".", true)])
-                        } else {
-                            dbus_interface.set_domains(tunnel_index, &[])
-                        };
+                        let tunnel_domains = compute_tunnel_domains(&routing_domains, &configs, tunnel_index);
+                        let tunnel_domain_refs: Vec<(&str, bool)> = tunnel_domains
+                            .iter()
+                            .map(|(domain, route_only)| (domain.as_str(), *route_only))
+                            .collect();
+                        let result = dbus_interface.set_domains(tunnel_index, &tunnel_domain_refs);
                         if let Err(err) = result {
                             log::error!("Failed to re-apply DNS domains - {}", err);
                         }
@@ -285,22 +832,97 @@ impl SystemdResolved {
             let _ = join_handle.await;
         }
 
-        for state in self.initial_states.lock().unwrap().drain(..) {
-            let result = if state.interface_index == self.tunnel_index {
-                self.dbus_interface.revert_link(state.clone()).await
-            } else {
-                self.dbus_interface.set_dns_state(state).await
-            };
-            if let Err(err) = result {
+        if let Some(forwarder) = self.forwarder.take() {
+            forwarder.shutdown().await;
+        }
+
+        if let Some(resolv_conf) = self.resolv_conf.take() {
+            if let Err(error) = resolv_conf.restore() {
                 log::error!(
                     "{}",
-                    err.display_chain_with_msg("Failed to revert interface config")
+                    error.display_chain_with_msg("Failed to restore /etc/resolv.conf")
                 );
             }
         }
 
+        if let Some(dbus_interface) = self.dbus_interface.clone() {
+            for state in self.initial_states.lock().unwrap().drain(..) {
+                let result = if state.interface_index == self.tunnel_index {
+                    dbus_interface.revert_link(state.clone()).await
+                } else {
+                    dbus_interface.set_dns_state(state).await
+                };
+                if let Err(err) = result {
+                    log::error!(
+                        "{}",
+                        err.display_chain_with_msg("Failed to revert interface config")
+                    );
+                }
+            }
+
+            for modes in self.initial_link_dns_modes.lock().unwrap().drain(..) {
+                if let Err(err) = dbus_interface
+                    .set_link_dns_over_tls(modes.interface_index, &modes.dns_over_tls)
+                    .await
+                {
+                    log::error!(
+                        "{}",
+                        err.display_chain_with_msg("Failed to restore DNS-over-TLS mode")
+                    );
+                }
+                if let Err(err) = dbus_interface
+                    .set_link_dnssec(modes.interface_index, &modes.dnssec)
+                    .await
+                {
+                    log::error!(
+                        "{}",
+                        err.display_chain_with_msg("Failed to restore DNSSEC mode")
+                    );
+                }
+            }
+        } else {
+            self.initial_states.lock().unwrap().clear();
+            self.initial_link_dns_modes.lock().unwrap().clear();
+        }
+
         self.current_config.lock().unwrap().clear();
 
         Ok(())
     }
+
+    /// Manages DNS via the built-in loopback forwarder instead of systemd-resolved, for systems
+    /// where the latter's D-Bus interface isn't available. Points `/etc/resolv.conf` at the
+    /// forwarder the first time it starts, so it's actually consulted for system-wide lookups;
+    /// `reset` restores whatever was there before.
+    async fn set_dns_via_forwarder(
+        &mut self,
+        servers: &[IpAddr],
+    ) -> Result<mpsc::UnboundedReceiver<DnsLeakEvent>> {
+        let forwarder_config = ForwarderConfig {
+            upstream_resolvers: servers.to_vec(),
+            routing_domains: self.routing_domains.clone(),
+        };
+
+        match &self.forwarder {
+            Some(forwarder) => forwarder.set_config(forwarder_config),
+            None => {
+                self.forwarder = Some(
+                    LoopbackDnsForwarder::start(forwarder_config)
+                        .await
+                        .map_err(Error::ForwarderError)?,
+                );
+                self.resolv_conf = Some(
+                    ManagedResolvConf::set(LOOPBACK_ADDR).map_err(Error::ResolvConfError)?,
+                );
+            }
+        }
+
+        *self.current_config.lock().unwrap() = vec![];
+
+        // The forwarder doesn't go through systemd-resolved, so there's nothing for the
+        // leak-detection subsystem to watch; the sender is dropped on return, so the receiver
+        // ends right away without yielding any events.
+        let (_leak_tx, leak_rx) = mpsc::unbounded();
+        Ok(leak_rx)
+    }
 }
@@ -0,0 +1,3 @@
+//! D-Bus bindings shared by `talpid-core`'s Linux backends.
+
+pub mod systemd_resolved;
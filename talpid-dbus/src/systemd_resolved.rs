@@ -0,0 +1,281 @@
+//! A thin async wrapper around systemd-resolved's `org.freedesktop.resolve1` D-Bus interface -
+//! specifically the per-link methods on `org.freedesktop.resolve1.Manager` that `talpid-core`'s
+//! `dns::linux::systemd_resolved` module drives. `dbus-rs` only exposes a blocking API, so every
+//! call here is dispatched onto a blocking thread via `tokio::task::spawn_blocking` and awaited,
+//! keeping the rest of the crate's DNS handling fully async.
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use dbus::blocking::{Connection, Proxy};
+
+const RESOLVE1_BUS_NAME: &str = "org.freedesktop.resolve1";
+const RESOLVE1_MANAGER_PATH: &str = "/org/freedesktop/resolve1";
+const RESOLVE1_MANAGER_INTERFACE: &str = "org.freedesktop.resolve1.Manager";
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to connect to the system D-Bus")]
+    ConnectDBus(#[error(source)] dbus::Error),
+
+    #[error(display = "systemd-resolved D-Bus call failed")]
+    DBusRpcError(#[error(source)] dbus::Error),
+}
+
+/// A resolver address systemd-resolved currently has configured on some link, as reported by
+/// `Manager.SubscribeDNSConfiguration`/`DNSConfiguration` D-Bus signals. Consumed by
+/// `AsyncHandle::watch_dns_changes`.
+#[derive(Debug, Clone)]
+pub struct ResolvedServer {
+    pub iface_index: i32,
+    pub address: IpAddr,
+}
+
+/// The DNS state of one systemd-resolved managed link, captured before we take it over so
+/// `AsyncHandle::set_dns_state`/`revert_link` can restore it in `SystemdResolved::reset`.
+#[derive(Debug, Clone)]
+pub struct DnsState {
+    pub interface_index: u32,
+    pub servers: Vec<IpAddr>,
+}
+
+/// Synchronous connection to systemd-resolved's D-Bus interface. Construction fails immediately
+/// if the service isn't reachable, which `talpid_core::dns::linux::SystemdResolved::new()` uses
+/// to decide whether to fall back to the loopback DNS forwarder instead.
+pub struct SystemdResolved {
+    connection: Arc<Connection>,
+}
+
+impl SystemdResolved {
+    pub fn new() -> Result<Self> {
+        let connection = Connection::new_system().map_err(Error::ConnectDBus)?;
+        // `Manager` always exists on the system bus while resolve1 is installed, but only
+        // responds once systemd-resolved.service is actually running - make sure it's alive
+        // before handing out a handle that callers will assume works.
+        let proxy = manager_proxy(&connection);
+        proxy
+            .method_call::<(), _, _, _>("org.freedesktop.DBus.Peer", "Ping", ())
+            .map_err(Error::ConnectDBus)?;
+
+        Ok(SystemdResolved {
+            connection: Arc::new(connection),
+        })
+    }
+
+    pub fn async_handle(&self) -> AsyncHandle {
+        AsyncHandle {
+            connection: self.connection.clone(),
+        }
+    }
+}
+
+fn manager_proxy(connection: &Connection) -> Proxy<'_, &Connection> {
+    connection.with_proxy(RESOLVE1_BUS_NAME, RESOLVE1_MANAGER_PATH, RPC_TIMEOUT)
+}
+
+/// Cloneable async handle to systemd-resolved, shared between the route-monitor task, the
+/// leak-detection poller, and the synchronous D-Bus watcher thread.
+#[derive(Clone)]
+pub struct AsyncHandle {
+    connection: Arc<Connection>,
+}
+
+impl AsyncHandle {
+    /// Returns a handle usable from blocking contexts (the watcher thread in
+    /// `spawn_watcher_thread` isn't async).
+    pub fn handle(&self) -> Self {
+        self.clone()
+    }
+
+    pub async fn get_dns(&self, interface_index: u32) -> Result<DnsState> {
+        self.call(move |proxy| {
+            let (servers,): (Vec<(i32, Vec<u8>)>,) = proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "GetLinkDNS",
+                (interface_index as i32,),
+            )?;
+            Ok(DnsState {
+                interface_index,
+                servers: servers
+                    .into_iter()
+                    .filter_map(|(_family, address)| parse_address(&address))
+                    .collect(),
+            })
+        })
+        .await
+    }
+
+    pub async fn set_dns(&self, interface_index: u32, servers: Vec<IpAddr>) -> Result<()> {
+        self.call(move |proxy| {
+            let servers: Vec<(i32, Vec<u8>)> =
+                servers.iter().map(|address| encode_address(*address)).collect();
+            proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "SetLinkDNS",
+                (interface_index as i32, servers),
+            )
+        })
+        .await
+    }
+
+    pub async fn set_dns_state(&self, state: DnsState) -> Result<()> {
+        self.set_dns(state.interface_index, state.servers).await
+    }
+
+    /// Reverts a link to systemd-resolved's own defaults rather than re-applying a captured
+    /// state, used for the tunnel interface on teardown since we own its whole lifetime.
+    pub async fn revert_link(&self, state: DnsState) -> Result<()> {
+        self.call(move |proxy| {
+            proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "RevertLink",
+                (state.interface_index as i32,),
+            )
+        })
+        .await
+    }
+
+    pub async fn set_domains(&self, interface_index: u32, domains: &[(&str, bool)]) -> Result<()> {
+        let domains: Vec<(String, bool)> = domains
+            .iter()
+            .map(|(domain, route_only)| (domain.to_string(), *route_only))
+            .collect();
+        self.call(move |proxy| {
+            proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "SetLinkDomains",
+                (interface_index as i32, domains),
+            )
+        })
+        .await
+    }
+
+    /// Enables or disables DNS-over-TLS enforcement on a link via `SetLinkDNSOverTLS`.
+    /// `mode` is one of `"yes"`, `"opportunistic"` or `"no"` (see `DnsOverTlsMode` in
+    /// `talpid_core::dns::linux`).
+    pub async fn set_link_dns_over_tls(&self, interface_index: u32, mode: &str) -> Result<()> {
+        let mode = mode.to_owned();
+        self.call(move |proxy| {
+            proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "SetLinkDNSOverTLS",
+                (interface_index as i32, mode),
+            )
+        })
+        .await
+    }
+
+    /// Reads back a link's current DNS-over-TLS mode, so it can be restored by `reset`.
+    pub async fn get_link_dns_over_tls(&self, interface_index: u32) -> Result<String> {
+        self.call(move |proxy| {
+            let (mode,): (String,) = proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "GetLinkDNSOverTLS",
+                (interface_index as i32,),
+            )?;
+            Ok(mode)
+        })
+        .await
+    }
+
+    /// Enables or disables DNSSEC validation on a link via `SetLinkDNSSEC`. `mode` is one of
+    /// `"yes"`, `"allow-downgrade"` or `"no"` (see `DnssecMode` in `talpid_core::dns::linux`).
+    pub async fn set_link_dnssec(&self, interface_index: u32, mode: &str) -> Result<()> {
+        let mode = mode.to_owned();
+        self.call(move |proxy| {
+            proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "SetLinkDNSSEC",
+                (interface_index as i32, mode),
+            )
+        })
+        .await
+    }
+
+    /// Reads back a link's current DNSSEC mode, so it can be restored by `reset`.
+    pub async fn get_link_dnssec(&self, interface_index: u32) -> Result<String> {
+        self.call(move |proxy| {
+            let (mode,): (String,) = proxy.method_call(
+                RESOLVE1_MANAGER_INTERFACE,
+                "GetLinkDNSSEC",
+                (interface_index as i32,),
+            )?;
+            Ok(mode)
+        })
+        .await
+    }
+
+    /// Subscribes to systemd-resolved's `DNSConfigurationChanged` signal and invokes `on_change`
+    /// with the full current server list every time it fires, until `should_continue` returns
+    /// `false`. Runs on the calling (blocking) thread - `spawn_watcher_thread` dedicates a whole
+    /// OS thread to this.
+    pub fn watch_dns_changes(
+        self,
+        on_change: impl Fn(Vec<ResolvedServer>) + Send + 'static,
+        should_continue: impl Fn() -> bool + Send + 'static,
+    ) -> Result<()> {
+        let proxy = manager_proxy(&self.connection);
+        let _subscription: dbus::message::MatchRule<'_> = dbus::message::MatchRule::new_signal(
+            RESOLVE1_MANAGER_INTERFACE,
+            "DNSConfigurationChanged",
+        );
+
+        proxy
+            .match_signal(
+                move |(_link, servers): (i32, Vec<(i32, i32, Vec<u8>)>), _: &Connection, _: &dbus::Message| {
+                    on_change(
+                        servers
+                            .into_iter()
+                            .filter_map(|(iface_index, _family, address)| {
+                                parse_address(&address)
+                                    .map(|address| ResolvedServer { iface_index, address })
+                            })
+                            .collect(),
+                    );
+                    true
+                },
+            )
+            .map_err(Error::DBusRpcError)?;
+
+        while should_continue() {
+            let _ = self.connection.process(Duration::from_millis(500));
+        }
+
+        Ok(())
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Proxy<'_, &Connection>) -> std::result::Result<T, dbus::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let proxy = manager_proxy(&connection);
+            f(&proxy).map_err(Error::DBusRpcError)
+        })
+        .await
+        .expect("systemd-resolved D-Bus task panicked")
+    }
+}
+
+fn parse_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+fn encode_address(address: IpAddr) -> (i32, Vec<u8>) {
+    match address {
+        IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+    }
+}